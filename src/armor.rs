@@ -0,0 +1,251 @@
+use std::io::Write;
+
+use failure::{err_msg, Error, ResultExt};
+
+use base64::{decode, encode};
+
+const BEGIN_MARKER: &'static str = "-----BEGIN PETER MESSAGE-----";
+const END_MARKER: &'static str = "-----END PETER MESSAGE-----";
+const LINE_LENGTH: usize = 64;
+const CRC24_INIT: u32 = 0x00B7_04CE;
+
+/// Fold one more byte into a running CRC-24 (same parameters as RFC 4880).
+fn crc24_step(mut crc: u32, byte: u8) -> u32 {
+    crc ^= (byte as u32) << 16;
+    for _ in 0..8 {
+        crc <<= 1;
+        if crc & 0x0100_0000 != 0 {
+            crc ^= 0x0186_4CFB;
+        }
+        crc &= 0x00FF_FFFF;
+    }
+    crc
+}
+
+/// Compute the CRC-24 checksum used by the armor footer (same parameters as RFC 4880).
+fn crc24(data: &[u8]) -> u32 {
+    data.iter().fold(CRC24_INIT, |crc, &byte| crc24_step(crc, byte))
+}
+
+/// Check whether `data` looks like an armored PETER message, without fully parsing it.
+pub fn is_armored(data: &[u8]) -> bool {
+    String::from_utf8_lossy(data).trim_start().starts_with(BEGIN_MARKER)
+}
+
+/// Wrap a raw ciphertext blob in a textual envelope that is safe to paste into email or a text
+/// file: a header line, the body base64-encoded and hard-wrapped, a CRC-24 checksum line and a
+/// footer line.
+pub fn armor(data: &[u8]) -> String {
+    let body = encode(data);
+
+    let mut out = String::new();
+    out.push_str(BEGIN_MARKER);
+    out.push_str("\n\n");
+    for line in body.as_bytes().chunks(LINE_LENGTH) {
+        out.push_str(std::str::from_utf8(line).unwrap());
+        out.push('\n');
+    }
+
+    let crc = crc24(data);
+    let crc_bytes = [(crc >> 16) as u8, (crc >> 8) as u8, crc as u8];
+    out.push('=');
+    out.push_str(&encode(&crc_bytes));
+    out.push('\n');
+    out.push_str(END_MARKER);
+    out.push('\n');
+    out
+}
+
+/// Incremental counterpart to [`armor`]: wraps ciphertext in the same textual envelope, but lets
+/// the caller feed it one block at a time instead of having to hold the whole message in memory
+/// first. Base64 encodes cleanly in independent 3-byte groups, and the CRC-24 folds in one byte
+/// at a time, so both can be computed block-by-block; only a sub-3-byte remainder and a
+/// partially-filled output line are ever held between calls.
+pub struct ArmorWriter<W: Write> {
+    out: W,
+    crc: u32,
+    leftover: Vec<u8>,
+    line: String,
+}
+
+impl<W: Write> std::fmt::Debug for ArmorWriter<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ArmorWriter")
+            .field("crc", &self.crc)
+            .field("leftover_len", &self.leftover.len())
+            .field("line_len", &self.line.len())
+            .finish()
+    }
+}
+
+impl<W: Write> ArmorWriter<W> {
+    pub fn new(mut out: W) -> Result<Self, Error> {
+        out.write_all(BEGIN_MARKER.as_bytes())
+            .context("Cannot write armor header to output.")?;
+        out.write_all(b"\n\n")
+            .context("Cannot write armor header to output.")?;
+        Ok(ArmorWriter {
+            out,
+            crc: CRC24_INIT,
+            leftover: Vec::with_capacity(2),
+            line: String::new(),
+        })
+    }
+
+    /// Feed the next block of raw ciphertext into the envelope.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        for &byte in data {
+            self.crc = crc24_step(self.crc, byte);
+        }
+
+        self.leftover.extend_from_slice(data);
+        let n_full = (self.leftover.len() / 3) * 3;
+        if n_full > 0 {
+            let encoded = encode(&self.leftover[..n_full]);
+            self.leftover.drain(..n_full);
+            self.push_encoded(&encoded)?;
+        }
+        Ok(())
+    }
+
+    fn push_encoded(&mut self, encoded: &str) -> Result<(), Error> {
+        self.line.push_str(encoded);
+        while self.line.len() >= LINE_LENGTH {
+            let rest = self.line.split_off(LINE_LENGTH);
+            self.out
+                .write_all(self.line.as_bytes())
+                .context("Cannot write armored body to output.")?;
+            self.out
+                .write_all(b"\n")
+                .context("Cannot write armored body to output.")?;
+            self.line = rest;
+        }
+        Ok(())
+    }
+
+    /// Flush any remaining bytes/line and write the CRC-24 checksum and footer.
+    pub fn finish(mut self) -> Result<(), Error> {
+        if !self.leftover.is_empty() {
+            let encoded = encode(&self.leftover);
+            self.push_encoded(&encoded)?;
+        }
+        if !self.line.is_empty() {
+            self.out
+                .write_all(self.line.as_bytes())
+                .context("Cannot write armored body to output.")?;
+            self.out
+                .write_all(b"\n")
+                .context("Cannot write armored body to output.")?;
+        }
+
+        let crc_bytes = [(self.crc >> 16) as u8, (self.crc >> 8) as u8, self.crc as u8];
+        self.out
+            .write_all(b"=")
+            .context("Cannot write armor checksum to output.")?;
+        self.out
+            .write_all(encode(&crc_bytes).as_bytes())
+            .context("Cannot write armor checksum to output.")?;
+        self.out
+            .write_all(b"\n")
+            .context("Cannot write armor checksum to output.")?;
+        self.out
+            .write_all(END_MARKER.as_bytes())
+            .context("Cannot write armor footer to output.")?;
+        self.out
+            .write_all(b"\n")
+            .context("Cannot write armor footer to output.")?;
+        Ok(())
+    }
+}
+
+/// Reverse of [`armor`]: parse the envelope, verify the CRC-24 checksum and return the raw
+/// ciphertext bytes.
+pub fn dearmor(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let text = String::from_utf8(data.to_vec()).context("Armored input is not valid UTF-8")?;
+    let mut lines = text.lines();
+
+    if !lines.by_ref().any(|line| line.trim() == BEGIN_MARKER) {
+        return Err(err_msg("Missing PETER MESSAGE armor header"));
+    }
+
+    let mut body = String::new();
+    let mut checksum_line: Option<String> = None;
+    for line in lines.by_ref() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if trimmed == END_MARKER {
+            break;
+        }
+        if trimmed.starts_with('=') {
+            checksum_line = Some(trimmed.to_string());
+            continue;
+        }
+        body.push_str(trimmed);
+    }
+
+    let checksum_line =
+        checksum_line.ok_or_else(|| err_msg("Missing armor checksum line"))?;
+    let decoded = decode(&body).context("Invalid base64 data in armored message")?;
+
+    let crc_bytes = decode(&checksum_line[1..]).context("Invalid base64 in armor checksum")?;
+    if crc_bytes.len() != 3 {
+        return Err(err_msg("Malformed armor checksum"));
+    }
+    let crc_expected =
+        ((crc_bytes[0] as u32) << 16) | ((crc_bytes[1] as u32) << 8) | (crc_bytes[2] as u32);
+    if crc_expected != crc24(&decoded) {
+        return Err(err_msg(
+            "Armor checksum does not match, message may be corrupt",
+        ));
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn armor_dearmor_round_trip() {
+        let data = b"the quick brown fox jumps over the lazy dog".repeat(10);
+        let armored = armor(&data);
+        assert!(is_armored(armored.as_bytes()));
+        assert_eq!(dearmor(armored.as_bytes()).unwrap(), data);
+    }
+
+    #[test]
+    fn armor_writer_matches_armor_when_fed_in_odd_sized_blocks() {
+        let data = b"the quick brown fox jumps over the lazy dog".repeat(10);
+
+        // deliberately chosen not to line up with the base64 3-byte grouping or the 64-char line
+        // wrap, so this exercises the writer's leftover/line carry-over logic
+        let mut out = Vec::new();
+        let mut writer = ArmorWriter::new(&mut out).unwrap();
+        for chunk in data.chunks(7) {
+            writer.write(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), armor(&data));
+    }
+
+    #[test]
+    fn dearmor_rejects_corrupt_checksum() {
+        let armored = armor(b"hello");
+        let corrupted: String = armored
+            .lines()
+            .map(|line| {
+                if line.starts_with('=') {
+                    "=AAAAAA".to_string()
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert!(dearmor(corrupted.as_bytes()).is_err());
+    }
+}
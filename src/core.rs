@@ -1,9 +1,12 @@
-use std::io::{Read, Write};
+use std::io::{Cursor, Read, Write};
 
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
 use failure::{err_msg, Error, ResultExt};
 use snow::params::NoiseParams;
+use snow::types::{Cipher, Hash, Random};
 use snow::{CryptoResolver, DefaultResolver, NoiseBuilder};
 
+use armor::{self, ArmorWriter};
 use ioutils::{open_reader, open_writer};
 
 lazy_static! {
@@ -17,10 +20,58 @@ const MAX_MESSAGE_LENGTH: usize = 65535;
 const PAYLOAD_BUFFER_LENGTH: usize = MAX_MESSAGE_LENGTH - OVERHEAD_PER_MESSAGE;
 const MAX_PAYLOAD_LENGTH: usize = PAYLOAD_BUFFER_LENGTH - MARKER_LENGTH;
 
-const PROLOGUE: &'static str = "PETER V2";
+// bumped whenever the wire format changes, so a mismatched version fails the handshake
+// cleanly instead of silently desyncing
+const PROLOGUE: &'static str = "PETER V3";
 const MARKER_NORMAL: u8 = 1;
 const MARKER_END: u8 = 2;
 
+const CONTENT_KEY_LENGTH: usize = 32;
+const SLOT_MESSAGE_LENGTH: usize = CONTENT_KEY_LENGTH + OVERHEAD_PER_MESSAGE;
+const SLOT_LENGTH: usize = HEADER_LENGTH + SLOT_MESSAGE_LENGTH;
+
+// enough bytes to see the armor header (with some slack for leading whitespace), so a huge
+// input only has to be fully buffered when it is actually armored, not on every decrypt
+const ARMOR_PEEK_LENGTH: usize = 64;
+
+// a generous upper bound on the number of recipient slots a message may list, so a corrupt or
+// hostile slot count can't drive a multi-gigabyte allocation attempt before it's even validated
+const MAX_RECIPIENT_SLOTS: usize = 1 << 16;
+
+// domain-separation label for shared-secret mode, so the derived key can never collide with a
+// hash computed for an unrelated purpose
+const SHARED_SECRET_LABEL: &'static str = "PETER shared-secret key derivation v1";
+
+// rekey the content key every `rekey_interval` messages, so a huge file does not keep reusing
+// one key for its entire length and does not march the nonce counter towards exhaustion. The
+// encryptor and decryptor derive the next key the same deterministic way, on the same message
+// boundary, so this invariant MUST match on both ends, or the stream desyncs after the first
+// rekey. The interval is configurable (this is only the default used when no `--rekey-interval`
+// is given) and is folded into the Noise prologue below, so a mismatched interval is caught as a
+// clean handshake failure instead of a silent desync partway through the stream.
+pub const DEFAULT_REKEY_INTERVAL: u64 = 1024;
+const REKEY_LABEL: &'static str = "PETER content key rekey v1";
+
+/// Build the Noise prologue for a session: the fixed wire-format marker plus the agreed rekey
+/// interval, so a sender/receiver mismatch on either one fails the handshake instead of
+/// desyncing the stream later on.
+fn build_prologue(rekey_interval: u64) -> Vec<u8> {
+    let mut prologue = PROLOGUE.as_bytes().to_vec();
+    prologue.extend_from_slice(&rekey_interval.to_be_bytes());
+    prologue
+}
+
+/// Deterministically derive the next content key from the current one, and install it in
+/// `cipher`, resetting the per-key nonce counter.
+fn rekey(cipher: &mut Box<Cipher>, content_key: &mut [u8; CONTENT_KEY_LENGTH]) {
+    let resolver = DefaultResolver::default();
+    let mut hash = resolver.resolve_hash(&PARAMS.hash).unwrap();
+    hash.input(REKEY_LABEL.as_bytes());
+    hash.input(content_key);
+    hash.result(content_key);
+    cipher.set(content_key);
+}
+
 pub fn gen_key() -> Box<[u8]> {
     let resolver = DefaultResolver::default();
     let mut dh = resolver.resolve_dh(&PARAMS.dh).unwrap();
@@ -29,6 +80,54 @@ pub fn gen_key() -> Box<[u8]> {
     dh.privkey().clone().into()
 }
 
+/// Deterministically derive an X25519 keypair from a passphrase: anyone who knows the same
+/// secret derives the identical keypair, so a small group can bootstrap encrypted
+/// communication from a memorized passphrase alone, with no key files to exchange.
+pub fn gen_key_from_secret(secret: &str) -> Box<[u8]> {
+    let resolver = DefaultResolver::default();
+    let mut hash = resolver.resolve_hash(&PARAMS.hash).unwrap();
+    hash.input(SHARED_SECRET_LABEL.as_bytes());
+    hash.input(secret.as_bytes());
+    let mut privkey = [0u8; CONTENT_KEY_LENGTH];
+    hash.result(&mut privkey);
+
+    // clamp per X25519 (RFC 7748): clear the low 3 bits of the first byte, clear the top bit
+    // and set bit 6 of the last byte
+    privkey[0] &= 0b1111_1000;
+    privkey[31] &= 0b0111_1111;
+    privkey[31] |= 0b0100_0000;
+
+    let mut dh = resolver.resolve_dh(&PARAMS.dh).unwrap();
+    dh.set(&privkey);
+    dh.privkey().clone().into()
+}
+
+#[cfg(test)]
+mod secret_key_tests {
+    use super::*;
+
+    #[test]
+    fn same_secret_derives_same_key() {
+        assert_eq!(
+            gen_key_from_secret("correct horse battery staple"),
+            gen_key_from_secret("correct horse battery staple")
+        );
+    }
+
+    #[test]
+    fn different_secrets_derive_different_keys() {
+        assert_ne!(
+            gen_key_from_secret("correct horse battery staple"),
+            gen_key_from_secret("hunter2")
+        );
+    }
+
+    #[test]
+    fn derived_key_differs_from_random_key() {
+        assert_ne!(gen_key_from_secret("correct horse battery staple"), gen_key());
+    }
+}
+
 pub fn extract_pubkey(privkey: Box<[u8]>) -> Box<[u8]> {
     let resolver = DefaultResolver::default();
     let mut dh = resolver.resolve_dh(&PARAMS.dh).unwrap();
@@ -36,42 +135,210 @@ pub fn extract_pubkey(privkey: Box<[u8]>) -> Box<[u8]> {
     dh.pubkey().clone().into()
 }
 
+/// Extract the Ed25519 verification key matching the signing key [`sign`] derives from
+/// `privkey`. This is a different curve point than [`extract_pubkey`]'s X25519 DH key (Ed25519
+/// hashes the seed through SHA-512 before clamping, X25519 doesn't), so a signature can only be
+/// verified against the key produced by this function, never the one produced by `pub`'s
+/// default, encryption-facing path.
+pub fn extract_sign_pubkey(privkey: &Box<[u8]>) -> Result<Box<[u8]>, Error> {
+    let secret = SecretKey::from_bytes(privkey).context("Invalid private key for signing")?;
+    let public = PublicKey::from(&secret);
+    Ok(public.to_bytes().to_vec().into_boxed_slice())
+}
+
+const DIGEST_LENGTH: usize = 32;
+
+/// Hash the whole content of `reader` with the suite's BLAKE2s, so signing and verification
+/// only ever have to deal with a fixed-size digest, no matter how large the file is.
+fn hash_reader(reader: &mut Box<Read>) -> Result<[u8; DIGEST_LENGTH], Error> {
+    let resolver = DefaultResolver::default();
+    let mut hash = resolver.resolve_hash(&PARAMS.hash).unwrap();
+
+    let mut buffer = [0u8; MAX_MESSAGE_LENGTH];
+    loop {
+        let s = reader
+            .read(&mut buffer)
+            .context("Cannot read input file for hashing")?;
+        if s == 0 {
+            break;
+        }
+        hash.input(&buffer[..s]);
+    }
+
+    let mut digest = [0u8; DIGEST_LENGTH];
+    hash.result(&mut digest);
+    Ok(digest)
+}
+
+/// Produce a detached signature over the BLAKE2s hash of `fin`, using `privkey` as an
+/// Ed25519 signing seed (the same 32 raw bytes used as an X25519 private key elsewhere).
+pub fn sign(privkey: &Box<[u8]>, fin: &String) -> Result<Box<[u8]>, Error> {
+    let mut fp_in = open_reader(fin)?;
+    let digest = hash_reader(&mut fp_in)?;
+
+    let secret = SecretKey::from_bytes(privkey).context("Invalid private key for signing")?;
+    let public = PublicKey::from(&secret);
+    let keypair = Keypair { secret, public };
+
+    let signature = keypair.sign(&digest);
+    Ok(signature.to_bytes().to_vec().into_boxed_slice())
+}
+
+/// Check a detached signature produced by [`sign`] against `fin` and `pubkey`.
+pub fn verify(pubkey: &Box<[u8]>, fin: &String, signature: &Box<[u8]>) -> Result<bool, Error> {
+    let mut fp_in = open_reader(fin)?;
+    let digest = hash_reader(&mut fp_in)?;
+
+    let public = PublicKey::from_bytes(pubkey).context("Invalid public key for verification")?;
+    let signature =
+        Signature::from_bytes(signature).context("Invalid signature data")?;
+
+    Ok(public.verify(&digest, &signature).is_ok())
+}
+
+#[cfg(test)]
+mod sign_tests {
+    use std::fs;
+
+    use super::*;
+
+    fn write_fixture(name: &str, contents: &[u8]) -> String {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn sign_verify_round_trip() {
+        let fin = write_fixture("peter-sign-round-trip", b"hello, signed world");
+        let privkey = gen_key();
+        let pubkey = extract_sign_pubkey(&privkey).unwrap();
+
+        let signature = sign(&privkey, &fin).unwrap();
+        assert!(verify(&pubkey, &fin, &signature).unwrap());
+    }
+
+    #[test]
+    fn verify_rejects_wrong_key() {
+        let fin = write_fixture("peter-sign-wrong-key", b"hello, signed world");
+        let privkey = gen_key();
+        let other_pubkey = extract_sign_pubkey(&gen_key()).unwrap();
+
+        let signature = sign(&privkey, &fin).unwrap();
+        assert!(!verify(&other_pubkey, &fin, &signature).unwrap());
+    }
+}
+
+/// Either writes straight through to the output file, or wraps it in an [`ArmorWriter`], so
+/// `encrypt` can feed both paths the same way without buffering the whole message up front.
+enum Sink<'a> {
+    Plain(&'a mut Box<Write>),
+    Armored(ArmorWriter<&'a mut Box<Write>>),
+}
+
+impl<'a> Sink<'a> {
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        match self {
+            Sink::Plain(out) => {
+                out.write(data).context("Cannot write to output file.")?;
+            }
+            Sink::Armored(writer) => writer.write(data)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        match self {
+            Sink::Plain(_) => Ok(()),
+            Sink::Armored(writer) => writer.finish(),
+        }
+    }
+}
+
 pub fn encrypt(
     privkey: &Box<[u8]>,
-    pubkey: &Box<[u8]>,
+    pubkeys: &[Box<[u8]>],
     fin: &String,
     fout: &String,
+    use_armor: bool,
+    rekey_interval: u64,
 ) -> Result<(), Error> {
+    if pubkeys.is_empty() {
+        return Err(err_msg("At least one recipient public key is required"));
+    }
+
     // open files
     let mut fp_in = open_reader(fin)?;
     let mut fp_out = open_writer(fout)?;
 
-    // set up noise protocol
-    let builder: NoiseBuilder = NoiseBuilder::new(PARAMS.clone());
-    let mut noise = builder
-        .local_private_key(&privkey)
-        .remote_public_key(&pubkey)
-        .prologue(PROLOGUE.as_bytes())
-        .build_initiator()
-        .context("Unable to set up noise session")?;
+    let resolver = DefaultResolver::default();
+
+    // generate a random content key and wrap a copy of it for every recipient via an
+    // independent Noise_X handshake, so the (possibly huge) file body only has to be
+    // encrypted once, symmetrically, no matter how many recipients there are
+    let mut content_key = [0u8; CONTENT_KEY_LENGTH];
+    {
+        let mut rng = resolver.resolve_rng().unwrap();
+        rng.fill_bytes(&mut content_key);
+    }
+
+    let mut slots = Vec::with_capacity(pubkeys.len());
+    for recipient in pubkeys {
+        let builder: NoiseBuilder = NoiseBuilder::new(PARAMS.clone());
+        let noise = builder
+            .local_private_key(&privkey)
+            .remote_public_key(recipient)
+            .prologue(&build_prologue(rekey_interval))
+            .build_initiator()
+            .context("Unable to set up noise session for recipient")?;
+
+        let mut slot = vec![0u8; SLOT_LENGTH];
+        let mut noise = noise;
+        let s_handshake = noise
+            .write_message(&[], &mut slot)
+            .context("Cannot create handshake data for recipient")?;
+        assert!(s_handshake == HEADER_LENGTH);
+
+        let mut noise = noise
+            .into_transport_mode()
+            .context("Cannot switch recipient session into transport state")?;
+        let s_message = noise
+            .write_message(&content_key, &mut slot[HEADER_LENGTH..])
+            .context("Cannot wrap content key for recipient")?;
+        assert!(s_message == SLOT_MESSAGE_LENGTH);
+
+        slots.push(slot);
+    }
+
+    // if armor is requested, wrap the output through an ArmorWriter, which base64-encodes and
+    // checksums block by block as it goes, so armoring a huge file doesn't require holding the
+    // whole thing in memory any more than the non-armored path does
+    let mut sink = if use_armor {
+        Sink::Armored(ArmorWriter::new(&mut fp_out)?)
+    } else {
+        Sink::Plain(&mut fp_out)
+    };
+
+    // header: number of recipient slots, followed by each slot's length, so the decryptor can
+    // scan the slots without having to guess their boundaries
+    let mut header = Vec::new();
+    header.extend_from_slice(&(slots.len() as u32).to_be_bytes());
+    for slot in &slots {
+        header.extend_from_slice(&(slot.len() as u32).to_be_bytes());
+    }
+    sink.write(&header)?;
+    for slot in &slots {
+        sink.write(slot)?;
+    }
+
+    // encrypt the file body once, symmetrically, with the content key, reusing the existing
+    // marker framing
+    let mut cipher = resolver.resolve_cipher(&PARAMS.cipher).unwrap();
+    cipher.set(&content_key);
 
-    // IO buffers
     let mut buffer_in = vec![0u8; PAYLOAD_BUFFER_LENGTH];
     let mut buffer_out = vec![0u8; MAX_MESSAGE_LENGTH];
-
-    // write intro
-    let s_out = noise
-        .write_message(&[], &mut buffer_out)
-        .context("Cannot create handshake data")?;
-    assert!(s_out == HEADER_LENGTH);
-    fp_out
-        .write(&buffer_out[..s_out])
-        .context("Cannot write handshake data to output file.")?;
-    let mut noise = noise
-        .into_transport_mode()
-        .context("Cannot switch session in transport state")?;
-
-    // encrypt payload
+    let mut nonce: u64 = 0;
     loop {
         let s_payload = fp_in
             .read(&mut buffer_in[MARKER_LENGTH..])
@@ -83,54 +350,180 @@ pub fn encrypt(
         };
         buffer_in[0] = marker;
 
-        let s_out = noise
-            .write_message(&buffer_in[..(MARKER_LENGTH + s_payload)], &mut buffer_out)
-            .context("Cannot encrypt block")?;
-        fp_out
-            .write(&buffer_out[..s_out])
-            .context("Cannot encrypted block to output file.")?;
+        let s_out = cipher.encrypt(
+            nonce,
+            &[],
+            &buffer_in[..(MARKER_LENGTH + s_payload)],
+            &mut buffer_out,
+        );
+        nonce += 1;
+        if nonce >= rekey_interval {
+            rekey(&mut cipher, &mut content_key);
+            nonce = 0;
+        }
+
+        sink.write(&buffer_out[..s_out])?;
 
         if marker == MARKER_END {
             break;
         }
     }
 
+    sink.finish()?;
+
     Ok(())
 }
 
 pub fn decrypt(
     privkey: &Box<[u8]>,
-    pubkey: &Option<Box<[u8]>>,
+    trusted_keys: &Option<Vec<Box<[u8]>>>,
     fin: &String,
     fout: &String,
+    use_armor: bool,
+    rekey_interval: u64,
 ) -> Result<Box<[u8]>, Error> {
     // open files
-    let mut fp_in = open_reader(fin)?;
+    let mut fp_in_raw = open_reader(fin)?;
     let mut fp_out = open_writer(fout)?;
 
-    // set up noise protocol
-    let builder: NoiseBuilder = NoiseBuilder::new(PARAMS.clone());
-    let mut noise = builder
-        .local_private_key(&privkey)
-        .prologue(PROLOGUE.as_bytes())
-        .build_responder()
-        .context("Unable to set up noise session")?;
+    // the armor header can only be told apart from a raw binary stream by looking at its first
+    // few bytes, so only peek that much up front; the rest of the (possibly huge) input keeps
+    // streaming unless it actually turns out to be armored, in which case the whole envelope has
+    // to be buffered anyway to verify its CRC-24 checksum
+    // `Read::read` is allowed to return short of a full buffer before EOF (true of stdin, pipes
+    // and sockets, all of which this tool accepts via `-`), so loop until the peek buffer is
+    // full or the input is actually exhausted, rather than trusting a single `read` call to see
+    // the whole marker
+    let mut peek = vec![0u8; ARMOR_PEEK_LENGTH];
+    let mut s_peek = 0;
+    while s_peek < peek.len() {
+        let n = fp_in_raw
+            .read(&mut peek[s_peek..])
+            .context("Cannot read input data.")?;
+        if n == 0 {
+            break;
+        }
+        s_peek += n;
+    }
+    peek.truncate(s_peek);
+
+    let mut fp_in: Box<Read> = if armor::is_armored(&peek) {
+        let mut raw = peek;
+        fp_in_raw
+            .read_to_end(&mut raw)
+            .context("Cannot read input data.")?;
+        Box::new(Cursor::new(armor::dearmor(&raw)?))
+    } else {
+        if use_armor {
+            return Err(err_msg("Expected armored input, but no armor header was found"));
+        }
+        Box::new(Cursor::new(peek).chain(fp_in_raw))
+    };
+
+    // read the recipient header: number of slots, followed by each slot's length
+    let mut count_buf = [0u8; 4];
+    fp_in
+        .read_exact(&mut count_buf)
+        .context("Cannot read recipient header from input file.")?;
+    let n_slots = u32::from_be_bytes(count_buf) as usize;
+    if n_slots == 0 {
+        return Err(err_msg("Message does not list any recipient slots"));
+    }
+    if n_slots > MAX_RECIPIENT_SLOTS {
+        return Err(err_msg("Message claims an implausible number of recipient slots"));
+    }
+
+    // every slot is produced by `encrypt` at the same fixed length (a Noise_X handshake plus one
+    // wrapped content key), so anything else in the header is corrupt or hostile input -- reject
+    // it here instead of trusting it to size an allocation
+    let mut slot_lengths = Vec::with_capacity(n_slots);
+    for _ in 0..n_slots {
+        let mut len_buf = [0u8; 4];
+        fp_in
+            .read_exact(&mut len_buf)
+            .context("Cannot read recipient slot length from input file.")?;
+        let slot_length = u32::from_be_bytes(len_buf) as usize;
+        if slot_length != SLOT_LENGTH {
+            return Err(err_msg("Recipient slot has an unexpected length"));
+        }
+        slot_lengths.push(slot_length);
+    }
+
+    let mut slots = Vec::with_capacity(n_slots);
+    for slot_length in slot_lengths {
+        let mut slot = vec![0u8; slot_length];
+        fp_in
+            .read_exact(&mut slot)
+            .context("Cannot read recipient slot from input file.")?;
+        slots.push(slot);
+    }
+
+    // try every slot with our private key and stop at the first one that authenticates and
+    // yields a content key
+    let resolver = DefaultResolver::default();
+    let mut content_key: Option<[u8; CONTENT_KEY_LENGTH]> = None;
+    let mut remote_static: Option<Box<[u8]>> = None;
+    for slot in &slots {
+        if slot.len() <= HEADER_LENGTH {
+            continue;
+        }
+
+        let builder: NoiseBuilder = NoiseBuilder::new(PARAMS.clone());
+        let noise = match builder
+            .local_private_key(&privkey)
+            .prologue(&build_prologue(rekey_interval))
+            .build_responder()
+        {
+            Ok(noise) => noise,
+            Err(_) => continue,
+        };
+
+        let mut scratch = vec![0u8; HEADER_LENGTH];
+        let mut noise = noise;
+        if noise.read_message(&slot[..HEADER_LENGTH], &mut scratch).is_err() {
+            continue;
+        }
+
+        let mut noise = match noise.into_transport_mode() {
+            Ok(noise) => noise,
+            Err(_) => continue,
+        };
+
+        let mut key_buffer = vec![0u8; slot.len() - HEADER_LENGTH];
+        let key_len = match noise.read_message(&slot[HEADER_LENGTH..], &mut key_buffer) {
+            Ok(key_len) => key_len,
+            Err(_) => continue,
+        };
+        if key_len != CONTENT_KEY_LENGTH {
+            continue;
+        }
+
+        let mut key = [0u8; CONTENT_KEY_LENGTH];
+        key.copy_from_slice(&key_buffer[..CONTENT_KEY_LENGTH]);
+        content_key = Some(key);
+        remote_static = noise.get_remote_static().map(|s| s.into());
+        break;
+    }
+
+    let mut content_key = content_key.ok_or_else(|| {
+        err_msg("None of the recipient slots could be decrypted with this private key")
+    })?;
+    let remote_static = remote_static
+        .ok_or_else(|| err_msg("Cannot extract senders static key from session state"))?;
+    if let Some(trusted) = trusted_keys {
+        if !trusted.iter().any(|key| &**key == &*remote_static) {
+            return Err(err_msg("Cannot verify senders key: not in the set of trusted keys"));
+        }
+    }
+
+    // decrypt the file body, which was encrypted once, symmetrically, with the content key
+    let mut cipher = resolver.resolve_cipher(&PARAMS.cipher).unwrap();
+    cipher.set(&content_key);
 
     // IO buffers
     let mut buffer_in = vec![0u8; MAX_MESSAGE_LENGTH];
     let mut buffer_out = vec![0u8; PAYLOAD_BUFFER_LENGTH];
-
-    // read intro
-    fp_in
-        .read_exact(&mut buffer_in[..HEADER_LENGTH])
-        .context("Cannot read handshake data from input file.")?;
-    let s_out = noise
-        .read_message(&buffer_in[..HEADER_LENGTH], &mut buffer_out)
-        .context("Cannot verify handshake data")?;
-    assert!(s_out == 0);
-    let mut noise = noise
-        .into_transport_mode()
-        .context("Cannot switch session in transport state")?;
+    let mut nonce: u64 = 0;
 
     // decrypt payload
     loop {
@@ -143,9 +536,14 @@ pub fn decrypt(
             ));
         }
 
-        let s_out = noise
-            .read_message(&buffer_in[..s_payload_enc], &mut buffer_out)
-            .context("Cannot decrypt block")?;
+        let s_out = cipher
+            .decrypt(nonce, &[], &buffer_in[..s_payload_enc], &mut buffer_out)
+            .map_err(|_| err_msg("Cannot decrypt block"))?;
+        nonce += 1;
+        if nonce >= rekey_interval {
+            rekey(&mut cipher, &mut content_key);
+            nonce = 0;
+        }
         fp_out
             .write(&buffer_out[MARKER_LENGTH..s_out])
             .context("Cannot write decrypted block to output file.")?;
@@ -172,14 +570,163 @@ pub fn decrypt(
         ));
     }
 
-    // check public key
-    let remote_static = noise
-        .get_remote_static()
-        .ok_or_else(|| err_msg("Cannot extract senders static key from session state"))?;
-    if let Some(pubkey_data) = pubkey {
-        if &**pubkey_data != remote_static {
-            return Err(err_msg("Cannot verify senders key"));
+    Ok(remote_static)
+}
+
+#[cfg(test)]
+mod encrypt_tests {
+    use std::fs;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(name).to_str().unwrap().to_string()
+    }
+
+    fn round_trip(n_recipients: usize) {
+        let sender_priv = gen_key();
+        let sender_pub = extract_pubkey(sender_priv.clone());
+        let recipients: Vec<Box<[u8]>> = (0..n_recipients).map(|_| gen_key()).collect();
+        let recipient_pubs: Vec<Box<[u8]>> = recipients
+            .iter()
+            .map(|privkey| extract_pubkey(privkey.clone()))
+            .collect();
+
+        let plaintext = b"the quick brown fox jumps over the lazy dog".repeat(1000);
+        let input_path = temp_path(&format!("peter-encrypt-test-input-{}", n_recipients));
+        let output_path = temp_path(&format!("peter-encrypt-test-output-{}", n_recipients));
+        fs::write(&input_path, &plaintext).unwrap();
+
+        encrypt(
+            &sender_priv,
+            &recipient_pubs,
+            &input_path,
+            &output_path,
+            false,
+            DEFAULT_REKEY_INTERVAL,
+        )
+        .unwrap();
+
+        for (i, recipient_priv) in recipients.iter().enumerate() {
+            let decrypted_path =
+                temp_path(&format!("peter-encrypt-test-decrypted-{}-{}", n_recipients, i));
+            let found_sender = decrypt(
+                recipient_priv,
+                &None,
+                &output_path,
+                &decrypted_path,
+                false,
+                DEFAULT_REKEY_INTERVAL,
+            )
+            .unwrap();
+            assert_eq!(found_sender, sender_pub);
+            assert_eq!(fs::read(&decrypted_path).unwrap(), plaintext);
         }
     }
-    Ok(remote_static.into())
+
+    #[test]
+    fn round_trip_single_recipient() {
+        round_trip(1);
+    }
+
+    #[test]
+    fn round_trip_multiple_recipients() {
+        round_trip(3);
+    }
+
+    #[test]
+    fn round_trip_armored() {
+        let sender_priv = gen_key();
+        let sender_pub = extract_pubkey(sender_priv.clone());
+        let recipient_priv = gen_key();
+        let recipient_pub = extract_pubkey(recipient_priv.clone());
+
+        // large enough to span several internal message blocks, so the armor writer's
+        // block-by-block encoding actually gets exercised rather than a single call
+        let plaintext = b"the quick brown fox jumps over the lazy dog".repeat(5000);
+        let input_path = temp_path("peter-encrypt-test-armored-input");
+        let output_path = temp_path("peter-encrypt-test-armored-output");
+        fs::write(&input_path, &plaintext).unwrap();
+
+        encrypt(
+            &sender_priv,
+            &[recipient_pub],
+            &input_path,
+            &output_path,
+            true,
+            DEFAULT_REKEY_INTERVAL,
+        )
+        .unwrap();
+
+        let armored = fs::read_to_string(&output_path).unwrap();
+        assert!(armor::is_armored(armored.as_bytes()));
+
+        let decrypted_path = temp_path("peter-encrypt-test-armored-decrypted");
+        let found_sender = decrypt(
+            &recipient_priv,
+            &None,
+            &output_path,
+            &decrypted_path,
+            true,
+            DEFAULT_REKEY_INTERVAL,
+        )
+        .unwrap();
+        assert_eq!(found_sender, sender_pub);
+        assert_eq!(fs::read(&decrypted_path).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn decrypt_rejects_oversized_recipient_header() {
+        let output_path = temp_path("peter-decrypt-test-oversized-header");
+        // a header claiming far more recipient slots than any real message would, and no slot
+        // data behind it -- this must fail cleanly instead of attempting a huge allocation
+        fs::write(&output_path, &u32::MAX.to_be_bytes()).unwrap();
+
+        let privkey = gen_key();
+        let decrypted_path = temp_path("peter-decrypt-test-oversized-header-out");
+        assert!(decrypt(
+            &privkey,
+            &None,
+            &output_path,
+            &decrypted_path,
+            false,
+            DEFAULT_REKEY_INTERVAL
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn decrypt_rejects_mismatched_rekey_interval() {
+        // the rekey interval is folded into the Noise prologue, so a receiver configured with a
+        // different interval than the sender used must fail the handshake cleanly instead of
+        // silently desyncing partway through the stream
+        let sender_priv = gen_key();
+        let recipient_priv = gen_key();
+        let recipient_pub = extract_pubkey(recipient_priv.clone());
+
+        let input_path = temp_path("peter-rekey-mismatch-input");
+        let output_path = temp_path("peter-rekey-mismatch-output");
+        fs::write(&input_path, b"hello, rekeyed world").unwrap();
+
+        encrypt(
+            &sender_priv,
+            &[recipient_pub],
+            &input_path,
+            &output_path,
+            false,
+            64,
+        )
+        .unwrap();
+
+        let decrypted_path = temp_path("peter-rekey-mismatch-decrypted");
+        assert!(decrypt(
+            &recipient_priv,
+            &None,
+            &output_path,
+            &decrypted_path,
+            false,
+            128,
+        )
+        .is_err());
+    }
 }
@@ -2,17 +2,138 @@ use std::fs::File;
 use std::io::{self, Read, Write};
 
 use base64::{decode, encode};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
 use failure::{err_msg, Error, ResultExt};
+use scrypt::{scrypt, ScryptParams};
+use snow::types::Random;
+use snow::{CryptoResolver, DefaultResolver};
 
 const WORLD_PRIVATE: &'static str = "4vQ4EoIcdkSn3liU4Fki9vyx1CsFb5RluE5gZnGfEyg=";
 const WORLD_PUBLIC: &'static str = "x+ssYnIlVuk9NkkxFbdXmNXCaAD0YB31aaUz5xsgPVI=";
 
+// self-describing tag identifying a passphrase-encrypted private key container, see
+// `encrypt_private_key`/`decrypt_private_key`
+const KEYFILE_MAGIC: &'static [u8] = b"PTRENCKEY1";
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+const DERIVED_KEY_LENGTH: usize = 32;
+const PRIVATE_KEY_LENGTH: usize = 32;
+
+// default scrypt cost parameters; chosen to be comfortable for an interactive passphrase
+// unlock, not for a low-power device
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+// upper bounds on the scrypt cost parameters we'll honor when reading a key file. These come
+// straight off untrusted input (a corrupt or hostile key file), so a value far above anything a
+// real passphrase unlock would use is rejected outright instead of being handed to scrypt(),
+// which would otherwise happily try to allocate and hash for an infeasible amount of memory/time
+const SCRYPT_LOG_N_MAX: u8 = 20;
+const SCRYPT_R_MAX: u32 = 16;
+const SCRYPT_P_MAX: u32 = 16;
+
 #[derive(Debug)]
 pub enum KeyType {
     Public,
     Private,
 }
 
+/// Derive a 32-byte key from a passphrase and salt using scrypt.
+fn derive_key(passphrase: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> Result<[u8; DERIVED_KEY_LENGTH], Error> {
+    // log_n/r/p may come straight off a key file we don't trust yet (see decrypt_private_key),
+    // so reject anything past a sane cap before it reaches scrypt() rather than letting a
+    // corrupt or hostile file drive an infeasible allocation/derivation
+    if log_n > SCRYPT_LOG_N_MAX || r > SCRYPT_R_MAX || p > SCRYPT_P_MAX {
+        return Err(err_msg("Scrypt cost parameters exceed the allowed maximum"));
+    }
+
+    let params =
+        ScryptParams::new(log_n, r, p).map_err(|_| err_msg("Invalid scrypt parameters"))?;
+    let mut derived = [0u8; DERIVED_KEY_LENGTH];
+    scrypt(passphrase.as_bytes(), salt, &params, &mut derived)
+        .map_err(|_| err_msg("Cannot derive key from passphrase"))?;
+    Ok(derived)
+}
+
+/// Wrap a 32-byte private key in a self-describing, passphrase-encrypted container: a magic
+/// tag, the scrypt cost parameters, a random salt, a random 24-byte nonce and the ChaChaPoly
+/// ciphertext (with its authentication tag).
+fn encrypt_private_key(privkey: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    let resolver = DefaultResolver::default();
+    let mut rng = resolver
+        .resolve_rng()
+        .ok_or_else(|| err_msg("Cannot set up random number generator"))?;
+
+    let mut salt = [0u8; SALT_LENGTH];
+    rng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rng.fill_bytes(&mut nonce);
+
+    let derived = derive_key(passphrase, &salt, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P)?;
+    let aead = XChaCha20Poly1305::new(Key::from_slice(&derived));
+    let ciphertext = aead
+        .encrypt(XNonce::from_slice(&nonce), privkey)
+        .map_err(|_| err_msg("Cannot encrypt private key"))?;
+
+    let mut container = Vec::with_capacity(
+        KEYFILE_MAGIC.len() + 1 + 4 + 4 + SALT_LENGTH + NONCE_LENGTH + ciphertext.len(),
+    );
+    container.extend_from_slice(KEYFILE_MAGIC);
+    container.push(SCRYPT_LOG_N);
+    container.extend_from_slice(&SCRYPT_R.to_be_bytes());
+    container.extend_from_slice(&SCRYPT_P.to_be_bytes());
+    container.extend_from_slice(&salt);
+    container.extend_from_slice(&nonce);
+    container.extend_from_slice(&ciphertext);
+    Ok(container)
+}
+
+/// Reverse of [`encrypt_private_key`]: parse the container, re-derive the key from the
+/// passphrase and authenticate the ciphertext, returning the plaintext private key.
+fn decrypt_private_key(container: &[u8], passphrase: &str) -> Result<Box<[u8]>, Error> {
+    let header_length = KEYFILE_MAGIC.len() + 1 + 4 + 4;
+    if container.len() < header_length + SALT_LENGTH + NONCE_LENGTH {
+        return Err(err_msg("Malformed encrypted key container"));
+    }
+
+    let mut offset = KEYFILE_MAGIC.len();
+    let log_n = container[offset];
+    offset += 1;
+    let r = u32::from_be_bytes([
+        container[offset],
+        container[offset + 1],
+        container[offset + 2],
+        container[offset + 3],
+    ]);
+    offset += 4;
+    let p = u32::from_be_bytes([
+        container[offset],
+        container[offset + 1],
+        container[offset + 2],
+        container[offset + 3],
+    ]);
+    offset += 4;
+
+    let salt = &container[offset..offset + SALT_LENGTH];
+    offset += SALT_LENGTH;
+    let nonce = &container[offset..offset + NONCE_LENGTH];
+    offset += NONCE_LENGTH;
+    let ciphertext = &container[offset..];
+
+    let derived = derive_key(passphrase, salt, log_n, r, p)?;
+    let aead = XChaCha20Poly1305::new(Key::from_slice(&derived));
+    let plaintext = aead
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| err_msg("Bad passphrase or corrupt key"))?;
+
+    if plaintext.len() != PRIVATE_KEY_LENGTH {
+        return Err(err_msg("Bad passphrase or corrupt key"));
+    }
+    Ok(plaintext.into())
+}
+
 pub fn is_stdinout(fname: &str) -> bool {
     fname == "-"
 }
@@ -25,7 +146,12 @@ pub fn is_world(fname: &str) -> bool {
     fname == "+"
 }
 
-pub fn write_key(fname: &String, data: Box<[u8]>, key_type: &KeyType) -> Result<(), Error> {
+pub fn write_key(
+    fname: &String,
+    data: Box<[u8]>,
+    key_type: &KeyType,
+    passphrase: &Option<String>,
+) -> Result<(), Error> {
     if is_none(&fname) {
         return Ok(());
     }
@@ -33,21 +159,28 @@ pub fn write_key(fname: &String, data: Box<[u8]>, key_type: &KeyType) -> Result<
         return Err(err_msg("Cannot write WORLD key."));
     }
 
-    // encode key data
-    let encoded = encode(&data);
+    let s: String = if let Some(passphrase) = passphrase {
+        match key_type {
+            KeyType::Public => return Err(err_msg("Cannot passphrase-protect a public key.")),
+            KeyType::Private => encode(&encrypt_private_key(&data, passphrase)?),
+        }
+    } else {
+        // encode key data
+        let encoded = encode(&data);
 
-    // check if key data belongs to WORLD
-    let s: String = match key_type {
-        KeyType::Public => if encoded == WORLD_PUBLIC {
-            "+".into()
-        } else {
-            encoded
-        },
-        KeyType::Private => if encoded == WORLD_PRIVATE {
-            "+".into()
-        } else {
-            encoded
-        },
+        // check if key data belongs to WORLD
+        match key_type {
+            KeyType::Public => if encoded == WORLD_PUBLIC {
+                "+".into()
+            } else {
+                encoded
+            },
+            KeyType::Private => if encoded == WORLD_PRIVATE {
+                "+".into()
+            } else {
+                encoded
+            },
+        }
     };
 
     // write data to actual output (stdout, file)
@@ -103,11 +236,126 @@ pub fn read_key(fname: &String, key_type: &KeyType) -> Result<Option<Box<[u8]>>,
     };
 
     // decode key data
-    Ok(Some(
-        decode(buffer.trim())
-            .context(format!("Invalid base64 data in key file: {}", fname))?
-            .into(),
-    ))
+    let raw = decode(buffer.trim()).context(format!("Invalid base64 data in key file: {}", fname))?;
+
+    // an encrypted private key is itself base64 data, but with a recognizable magic tag, so it
+    // can be told apart from a legacy, plain-text key file
+    if raw.starts_with(KEYFILE_MAGIC) {
+        let passphrase = rpassword::prompt_password_stdout(&format!("Passphrase for {}: ", fname))
+            .context("Cannot read passphrase")?;
+        return Ok(Some(decrypt_private_key(&raw, &passphrase)?));
+    }
+
+    Ok(Some(raw.into()))
+}
+
+/// Read a set of trusted public keys, either a comma-separated list of key files/sources (as
+/// accepted by [`read_key`]) or a single file holding one base64-encoded key per line.
+pub fn read_keys(fname: &String) -> Result<Option<Vec<Box<[u8]>>>, Error> {
+    if is_none(&fname) {
+        return Ok(None);
+    }
+
+    if fname.contains(',') {
+        let keys: Result<Vec<Box<[u8]>>, Error> = fname
+            .split(',')
+            .map(|part| {
+                read_key(&part.to_string(), &KeyType::Public)?
+                    .ok_or_else(|| err_msg(format!("No public key found for: {}", part)))
+            })
+            .collect();
+        return Ok(Some(keys?));
+    }
+
+    if is_world(&fname) || is_stdinout(&fname) {
+        return Ok(Some(vec![
+            read_key(fname, &KeyType::Public)?
+                .ok_or_else(|| err_msg(format!("No public key found for: {}", fname)))?,
+        ]));
+    }
+
+    let mut buffer = String::new();
+    let mut file = File::open(fname).context(format!("Could not open key file: {}", fname))?;
+    file.read_to_string(&mut buffer)
+        .context(format!("Could not read keys as string: {}", fname))?;
+
+    let mut keys = Vec::new();
+    for line in buffer.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let raw = if is_world(trimmed) { WORLD_PUBLIC } else { trimmed };
+        let decoded =
+            decode(raw).context(format!("Invalid base64 data in key file: {}", fname))?;
+        keys.push(decoded.into());
+    }
+
+    if keys.is_empty() {
+        return Err(err_msg(format!("No public keys found in: {}", fname)));
+    }
+    Ok(Some(keys))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(name).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn read_keys_multi_line_file() {
+        let path = temp_path("peter-read-keys-multiline");
+        fs::write(&path, format!("{}\n{}\n", encode(&[1u8; 32]), encode(&[2u8; 32]))).unwrap();
+
+        let keys = read_keys(&path).unwrap().unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(&*keys[0], &[1u8; 32][..]);
+        assert_eq!(&*keys[1], &[2u8; 32][..]);
+    }
+
+    #[test]
+    fn read_keys_comma_separated_files() {
+        let path_a = temp_path("peter-read-keys-comma-a");
+        let path_b = temp_path("peter-read-keys-comma-b");
+        fs::write(&path_a, encode(&[3u8; 32])).unwrap();
+        fs::write(&path_b, encode(&[4u8; 32])).unwrap();
+
+        let combined = format!("{},{}", path_a, path_b);
+        let keys = read_keys(&combined).unwrap().unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(&*keys[0], &[3u8; 32][..]);
+        assert_eq!(&*keys[1], &[4u8; 32][..]);
+    }
+
+    #[test]
+    fn encrypt_decrypt_private_key_round_trip() {
+        let privkey = [7u8; PRIVATE_KEY_LENGTH];
+        let container = encrypt_private_key(&privkey, "hunter2").unwrap();
+        assert!(container.starts_with(KEYFILE_MAGIC));
+
+        let decrypted = decrypt_private_key(&container, "hunter2").unwrap();
+        assert_eq!(&*decrypted, &privkey[..]);
+    }
+
+    #[test]
+    fn decrypt_private_key_rejects_wrong_passphrase() {
+        let privkey = [7u8; PRIVATE_KEY_LENGTH];
+        let container = encrypt_private_key(&privkey, "hunter2").unwrap();
+        assert!(decrypt_private_key(&container, "wrong").is_err());
+    }
+
+    #[test]
+    fn derive_key_rejects_oversized_parameters() {
+        let salt = [0u8; SALT_LENGTH];
+        assert!(derive_key("pw", &salt, SCRYPT_LOG_N_MAX + 1, SCRYPT_R, SCRYPT_P).is_err());
+        assert!(derive_key("pw", &salt, SCRYPT_LOG_N, SCRYPT_R_MAX + 1, SCRYPT_P).is_err());
+        assert!(derive_key("pw", &salt, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P_MAX + 1).is_err());
+    }
 }
 
 pub fn open_reader(fname: &String) -> Result<Box<Read>, Error> {
@@ -2,21 +2,29 @@
 #![deny(unused_extern_crates)]
 
 extern crate base64;
+extern crate chacha20poly1305;
+extern crate ed25519_dalek;
 extern crate failure;
 #[macro_use]
 extern crate lazy_static;
+extern crate rpassword;
+extern crate scrypt;
 extern crate snow;
 #[macro_use]
 extern crate quicli;
 
+mod armor;
 mod core;
 mod ioutils;
 
 use failure::err_msg;
 use quicli::prelude::*;
 
-use core::{decrypt, encrypt, extract_pubkey, gen_key};
-use ioutils::{is_none, is_stdinout, read_key, write_key, KeyType};
+use core::{
+    decrypt, encrypt, extract_pubkey, extract_sign_pubkey, gen_key, gen_key_from_secret, sign,
+    verify,
+};
+use ioutils::{is_stdinout, read_key, read_keys, write_key, KeyType};
 
 /// Simple encryption tool
 #[derive(Debug, StructOpt)]
@@ -36,6 +44,16 @@ enum Command {
         /// Where to store the private key to, file or '-' (stdout)
         #[structopt(default_value = "-")]
         output: String,
+
+        /// Protect the private key at rest with a passphrase (you will be prompted for it)
+        #[structopt(long = "passphrase", short = "P")]
+        passphrase: bool,
+
+        /// Deterministically derive the key from a shared secret instead of generating it
+        /// randomly (you will be prompted for the secret); anyone who knows the same secret
+        /// derives the identical keypair
+        #[structopt(long = "from-secret")]
+        from_secret: bool,
     },
 
     /// Extract public key from private key
@@ -48,6 +66,11 @@ enum Command {
         /// Where to store the public key to, file or '-' (stdout)
         #[structopt(default_value = "-")]
         output: String,
+
+        /// Derive the Ed25519 signature-verification key (for use with `verify`) instead of the
+        /// X25519 encryption key; these are different curve points and are not interchangeable
+        #[structopt(long = "sign")]
+        sign: bool,
     },
 
     /// Encrypt data
@@ -56,7 +79,8 @@ enum Command {
         /// Where to read your private key from, file or '-' (stdin)
         privkey: String,
 
-        /// Where to read the recipients public key from, file or '-' (stdin)
+        /// Where to read the recipients' public keys from: a single file or '-' (stdin), or a
+        /// comma-separated list of files to encrypt for several recipients at once
         pubkey: String,
 
         /// Where to read the input data from, file; stdin is NOT supported!
@@ -65,6 +89,16 @@ enum Command {
         /// Where to store the encrypted data to, file or '-' (stdout)
         #[structopt(default_value = "-")]
         output: String,
+
+        /// Wrap the output in an ASCII-armored, text-safe envelope
+        #[structopt(long = "armor", short = "a")]
+        armor: bool,
+
+        /// Rekey the content key every this many messages; the decryptor must be given the same
+        /// value, or the handshake will fail cleanly instead of the stream silently desyncing.
+        /// Default matches `core::DEFAULT_REKEY_INTERVAL`.
+        #[structopt(long = "rekey-interval", default_value = "1024")]
+        rekey_interval: u64,
     },
 
     /// Decrypt data
@@ -73,7 +107,9 @@ enum Command {
         /// Where to read your private key from, file or '-' (stdin)
         privkey: String,
 
-        /// Where to read the senders public key from, file or '-' (stdin) or '.' (ignore)
+        /// Trusted senders' public key(s): a file, '-' (stdin) or '.' (accept anybody); also
+        /// accepts a comma-separated list of files, or a single file with one base64 key per
+        /// line, to trust a whole set of known correspondents
         #[structopt(default_value = ".")]
         pubkey: String,
 
@@ -88,6 +124,45 @@ enum Command {
         /// Where to write the senders public key to, file or '-' (stdout) or '.' (ignore)
         #[structopt(default_value = ".")]
         foundkey: String,
+
+        /// Require the input to be an ASCII-armored envelope (armored input is always detected
+        /// and unwrapped transparently, armor or not)
+        #[structopt(long = "armor", short = "a")]
+        armor: bool,
+
+        /// The rekey interval the sender used for this message (see `enc --rekey-interval`);
+        /// must match exactly, or the handshake will fail cleanly instead of the stream silently
+        /// desyncing. Default matches `core::DEFAULT_REKEY_INTERVAL`.
+        #[structopt(long = "rekey-interval", default_value = "1024")]
+        rekey_interval: u64,
+    },
+
+    /// Create a detached signature over a file
+    #[structopt(name = "sign")]
+    Sign {
+        /// Where to read your private key from, file or '-' (stdin)
+        privkey: String,
+
+        /// File to sign
+        input: String,
+
+        /// Where to store the detached signature to, file or '-' (stdout)
+        #[structopt(default_value = "-")]
+        output: String,
+    },
+
+    /// Check a detached signature over a file
+    #[structopt(name = "verify")]
+    Verify {
+        /// Where to read the signers public key from, file or '-' (stdin); must be the key
+        /// produced by `peter pub --sign`, not the default encryption key
+        pubkey: String,
+
+        /// File the signature was made over
+        input: String,
+
+        /// Where to read the detached signature from, file or '-' (stdin)
+        signature: String,
     },
 }
 
@@ -95,34 +170,72 @@ main!(|args: Cli, log_level: verbosity| {
     info!("started");
 
     match args.command {
-        Command::Generate { output } => {
+        Command::Generate {
+            output,
+            passphrase,
+            from_secret,
+        } => {
             info!("generating key");
-            let key = gen_key();
+            let key = if from_secret {
+                let entered = rpassword::prompt_password_stdout("Shared secret: ")
+                    .map_err(|_| err_msg("Cannot read shared secret"))?;
+                let confirmed = rpassword::prompt_password_stdout("Confirm shared secret: ")
+                    .map_err(|_| err_msg("Cannot read shared secret"))?;
+                if entered != confirmed {
+                    return Err(err_msg("Shared secrets do not match"));
+                }
+                gen_key_from_secret(&entered)
+            } else {
+                gen_key()
+            };
+
+            let passphrase = if passphrase {
+                let entered = rpassword::prompt_password_stdout("Passphrase: ")
+                    .map_err(|_| err_msg("Cannot read passphrase"))?;
+                let confirmed = rpassword::prompt_password_stdout("Confirm passphrase: ")
+                    .map_err(|_| err_msg("Cannot read passphrase"))?;
+                if entered != confirmed {
+                    return Err(err_msg("Passphrases do not match"));
+                }
+                Some(entered)
+            } else {
+                None
+            };
 
             info!("write to output ({})", output);
-            write_key(&output, key, &KeyType::Private)?;
+            write_key(&output, key, &KeyType::Private, &passphrase)?;
         }
-        Command::PubKey { input, output } => {
+        Command::PubKey { input, output, sign } => {
             info!("read private key ({})", input);
             let privkey = read_key(&input, &KeyType::Private)?
                 .ok_or_else(|| err_msg("No private key provided"))?;
 
             info!("extracting public key");
-            let pubkey = extract_pubkey(privkey);
+            let pubkey = if sign {
+                extract_sign_pubkey(&privkey)?
+            } else {
+                extract_pubkey(privkey)
+            };
 
             info!("write to output ({})", output);
-            write_key(&output, pubkey, &KeyType::Public)?;
+            write_key(&output, pubkey, &KeyType::Public, &None)?;
         }
         Command::Encrypt {
             input,
             output,
             privkey,
             pubkey,
+            armor,
+            rekey_interval,
         } => {
-            let n_stdin: u8 = vec![&input, &privkey, &pubkey]
+            let n_stdin: u8 = vec![&input, &privkey]
                 .iter()
                 .map(|s| is_stdinout(s) as u8)
-                .sum();
+                .sum::<u8>()
+                + pubkey
+                    .split(',')
+                    .map(|s| is_stdinout(s) as u8)
+                    .sum::<u8>();
             if n_stdin > 1 {
                 return Err(err_msg("You can at most have one file read from stdin!"));
             }
@@ -131,12 +244,18 @@ main!(|args: Cli, log_level: verbosity| {
             let privkey = read_key(&privkey, &KeyType::Private)?
                 .ok_or_else(|| err_msg("No private key provided"))?;
 
-            info!("read public key ({})", pubkey);
-            let pubkey = read_key(&pubkey, &KeyType::Public)?
-                .ok_or_else(|| err_msg("No public key provided"))?;
+            info!("read public key(s) ({})", pubkey);
+            let pubkeys: Result<Vec<Box<[u8]>>> = pubkey
+                .split(',')
+                .map(|p| {
+                    read_key(&p.to_string(), &KeyType::Public)?
+                        .ok_or_else(|| err_msg(format!("No public key provided ({})", p)))
+                })
+                .collect();
+            let pubkeys = pubkeys?;
 
             info!("encrypting");
-            encrypt(&privkey, &pubkey, &input, &output)?;
+            encrypt(&privkey, &pubkeys, &input, &output, armor, rekey_interval)?;
         }
         Command::Decrypt {
             input,
@@ -144,11 +263,17 @@ main!(|args: Cli, log_level: verbosity| {
             privkey,
             pubkey,
             foundkey,
+            armor,
+            rekey_interval,
         } => {
-            let n_stdin: u8 = vec![&input, &privkey, &pubkey]
+            let n_stdin: u8 = vec![&input, &privkey]
                 .iter()
                 .map(|s| is_stdinout(s) as u8)
-                .sum();
+                .sum::<u8>()
+                + pubkey
+                    .split(',')
+                    .map(|s| is_stdinout(s) as u8)
+                    .sum::<u8>();
             if n_stdin > 1 {
                 return Err(err_msg("You can at most have one file read from stdin!"));
             }
@@ -165,20 +290,47 @@ main!(|args: Cli, log_level: verbosity| {
             let privkey = read_key(&privkey, &KeyType::Private)?
                 .ok_or_else(|| err_msg("No private key provided"))?;
 
-            let pubkey = if is_none(&pubkey) {
-                info!("no public key provided");
-                None
-            } else {
-                info!("read public key ({})", pubkey);
-                Some(
-                    read_key(&pubkey, &KeyType::Public)?
-                        .ok_or_else(|| err_msg("No public key provided"))?,
-                )
-            };
+            info!("read trusted public key(s) ({})", pubkey);
+            let trusted_keys = read_keys(&pubkey)?;
 
             info!("decrypting");
-            let pubkey2 = decrypt(&privkey, &pubkey, &input, &output)?;
-            write_key(&foundkey, pubkey2, &KeyType::Public)?;
+            let pubkey2 = decrypt(&privkey, &trusted_keys, &input, &output, armor, rekey_interval)?;
+            write_key(&foundkey, pubkey2, &KeyType::Public, &None)?;
+        }
+        Command::Sign {
+            privkey,
+            input,
+            output,
+        } => {
+            info!("read private key ({})", privkey);
+            let privkey = read_key(&privkey, &KeyType::Private)?
+                .ok_or_else(|| err_msg("No private key provided"))?;
+
+            info!("signing ({})", input);
+            let signature = sign(&privkey, &input)?;
+
+            info!("write signature to output ({})", output);
+            write_key(&output, signature, &KeyType::Public, &None)?;
+        }
+        Command::Verify {
+            pubkey,
+            input,
+            signature,
+        } => {
+            info!("read public key ({})", pubkey);
+            let pubkey = read_key(&pubkey, &KeyType::Public)?
+                .ok_or_else(|| err_msg("No public key provided"))?;
+
+            info!("read signature ({})", signature);
+            let signature = read_key(&signature, &KeyType::Public)?
+                .ok_or_else(|| err_msg("No signature provided"))?;
+
+            info!("verifying ({})", input);
+            if verify(&pubkey, &input, &signature)? {
+                println!("OK");
+            } else {
+                return Err(err_msg("Signature verification FAILED"));
+            }
         }
     }
     info!("done");